@@ -1,59 +1,266 @@
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::hash::Hash;
 use std::rc::Rc;
 
 use aes_gcm::aead::{Aead, KeyInit, OsRng};
 use aes_gcm::{Aes256Gcm, Nonce};
 
 #[derive(Debug)]
-enum HuffmanNode {
+enum HuffmanNode<S> {
     Leaf {
-        byte: u8,
+        symbol: S,
     },
     Internal {
-        left: Rc<HuffmanNode>,
-        right: Rc<HuffmanNode>,
+        left: Rc<HuffmanNode<S>>,
+        right: Rc<HuffmanNode<S>>,
     },
 }
 
+/// A Huffman tree over an alphabet of `S`. `S` is usually `u8` (raw bytes),
+/// but `build`, `encode`, `decode_exact` and `from_codes` work for any
+/// `Eq + Hash + Clone` symbol type, e.g. `char` for text or a word-ID type
+/// for tokenized input. The canonical code-length header, packed encoding
+/// and `compress`/`decompress` are only provided for `HuffmanTree<u8>`.
 #[derive(Debug)]
-struct HuffmanTree {
-    root: Rc<HuffmanNode>,
-    codes: HashMap<u8, Vec<bool>>,
+struct HuffmanTree<S: Eq + Hash + Clone> {
+    root: Rc<HuffmanNode<S>>,
+    codes: HashMap<S, Vec<bool>>,
 }
 
 #[derive(Debug)]
-struct HeapNode {
+struct HeapNode<S> {
     freq: usize,
-    node: Rc<HuffmanNode>,
+    node: Rc<HuffmanNode<S>>,
 }
 
-impl PartialEq for HeapNode {
+impl<S> PartialEq for HeapNode<S> {
     fn eq(&self, other: &Self) -> bool {
         self.freq == other.freq
     }
 }
-impl Eq for HeapNode {}
-impl PartialOrd for HeapNode {
+impl<S> Eq for HeapNode<S> {}
+impl<S> PartialOrd for HeapNode<S> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
-impl Ord for HeapNode {
+impl<S> Ord for HeapNode<S> {
     fn cmp(&self, other: &Self) -> Ordering {
         other.freq.cmp(&self.freq)
     }
 }
 
-impl HuffmanTree {
-    pub fn build(freqs: &HashMap<u8, usize>) -> Self {
+/// Errors that can occur while reconstructing a tree and decoding a stream
+/// produced by [`HuffmanTree::compress`].
+#[derive(Debug, PartialEq, Eq)]
+enum DecodeError {
+    /// The byte stream ended before a complete header or bitstream could be read.
+    UnexpectedEof,
+    /// The bits left over after the last full symbol weren't the EOS padding
+    /// `BitWriter::finish` writes, so the stream is corrupt or truncated.
+    BadPadding,
+    /// The header's per-symbol code lengths don't form a valid canonical code.
+    MalformedCodeTable(HuffmanTreeError),
+    /// The header claims a nonzero `original_len` but carries no symbols.
+    EmptyCodeTable,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => {
+                write!(f, "compressed stream ended before it could be fully read")
+            }
+            DecodeError::BadPadding => {
+                write!(f, "trailing bits after the last symbol were not valid EOS padding")
+            }
+            DecodeError::MalformedCodeTable(err) => {
+                write!(f, "header's code lengths don't form a valid code: {err}")
+            }
+            DecodeError::EmptyCodeTable => {
+                write!(f, "header claims a nonzero original length but carries no symbols")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<HuffmanTreeError> for DecodeError {
+    fn from(err: HuffmanTreeError) -> Self {
+        DecodeError::MalformedCodeTable(err)
+    }
+}
+
+/// Errors that can occur while validating a code table, whether given
+/// explicitly to [`HuffmanTree::from_codes`] or reconstructed from lengths
+/// by [`HuffmanTree::from_code_lengths`].
+// Shared `Leaf` suffix is intentional (each variant is a different way a
+// leaf ends up misplaced), not an enum-variant-names violation.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq, Eq)]
+enum HuffmanTreeError {
+    /// Two symbols were given the exact same code.
+    DuplicateLeaf,
+    /// A code is a strict prefix of another, longer code, so the node
+    /// where the longer code continues would have to be both a leaf and
+    /// an internal node.
+    OrphanedLeaf,
+    /// An internal node was reached by some code but never given both a
+    /// `0` and a `1` child, so the table isn't a complete prefix code.
+    MissingLeaf,
+}
+
+impl fmt::Display for HuffmanTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HuffmanTreeError::DuplicateLeaf => write!(f, "two symbols were given the same code"),
+            HuffmanTreeError::OrphanedLeaf => {
+                write!(f, "a code is a prefix of another, longer code")
+            }
+            HuffmanTreeError::MissingLeaf => {
+                write!(f, "code table is incomplete: some node has an unused branch")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HuffmanTreeError {}
+
+/// Reads individual bits out of a byte slice, most significant bit first.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, pos: 0 }
+    }
+
+    fn total_bits(&self) -> usize {
+        self.bytes.len() * 8
+    }
+
+    fn bit_at(&self, index: usize) -> bool {
+        let byte = self.bytes[index / 8];
+        (byte >> (7 - index % 8)) & 1 == 1
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        if self.pos >= self.total_bits() {
+            return None;
+        }
+        let bit = self.bit_at(self.pos);
+        self.pos += 1;
+        Some(bit)
+    }
+
+    /// Confirms that every bit from `from` to the end of the stream is a
+    /// padding `1` (the convention `BitWriter::finish` pads with, borrowed
+    /// from HPACK's Huffman EOS marker) and that there are fewer than 8 of
+    /// them, i.e. at most the tail of one partial byte.
+    fn verify_ending(&self, from: usize) -> Result<(), DecodeError> {
+        let total = self.total_bits();
+        if total - from >= 8 {
+            return Err(DecodeError::BadPadding);
+        }
+        if (from..total).all(|i| self.bit_at(i)) {
+            Ok(())
+        } else {
+            Err(DecodeError::BadPadding)
+        }
+    }
+}
+
+/// Packs individual bits into bytes, most significant bit first. A trailing
+/// partial byte is padded with `1` bits rather than `0`s, so
+/// `BitReader::verify_ending` can tell real code bits from padding.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits_in_last_byte: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            bits_in_last_byte: 0,
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        if self.bits_in_last_byte == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 1 << (7 - self.bits_in_last_byte);
+        }
+        self.bits_in_last_byte = (self.bits_in_last_byte + 1) % 8;
+    }
+
+    fn push_bits(&mut self, bits: &[bool]) {
+        for &bit in bits {
+            self.push_bit(bit);
+        }
+    }
+
+    /// Pushes the low `bits` bits of `value`, most-significant-bit first.
+    /// This is the table-driven counterpart to `push_bits` that
+    /// [`HuffmanTree::encode_packed`] uses: a code that's already packed
+    /// into a `HuffmanValue` can be shifted straight into the output byte
+    /// buffer without ever materializing it as a `Vec<bool>`.
+    ///
+    /// `bits` must be at most 64: `value >> i` for `i >= 64` is a shift
+    /// overflow. `HuffmanValue` only ever packs codes that fit, so this
+    /// is an invariant of the caller rather than something to handle here.
+    fn push_value(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 64, "HuffmanValue must not pack codes longer than 64 bits");
+        for i in (0..bits).rev() {
+            self.push_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        while self.bits_in_last_byte != 0 {
+            self.push_bit(true);
+        }
+        self.bytes
+    }
+}
+
+impl<S: Eq + Hash + Clone> HuffmanTree<S> {
+    /// Builds a tree from a frequency table. The tie-breaking order among
+    /// equal-frequency symbols isn't guaranteed, so two trees built from the
+    /// same table aren't guaranteed to come out structurally identical;
+    /// `compress` works around this by canonicalizing the result through
+    /// `code_lengths`/`from_code_lengths` rather than relying on `build`'s
+    /// own codes being reproducible.
+    pub fn build(freqs: &HashMap<S, usize>) -> Self {
         let mut heap = BinaryHeap::new();
 
-        for (&byte, &freq) in freqs {
-            let node = Rc::new(HuffmanNode::Leaf { byte });
+        for (symbol, &freq) in freqs {
+            let node = Rc::new(HuffmanNode::Leaf { symbol: symbol.clone() });
             heap.push(HeapNode { freq, node });
         }
 
+        // A single-symbol alphabet would otherwise collapse to a bare leaf
+        // root with an empty code, which can't represent repeat counts.
+        // Force a one-bit code by giving it a sibling of itself.
+        if heap.len() == 1 {
+            let only = heap.pop().unwrap();
+            let node = Rc::new(HuffmanNode::Internal {
+                left: Rc::clone(&only.node),
+                right: only.node,
+            });
+            heap.push(HeapNode {
+                freq: only.freq,
+                node,
+            });
+        }
+
         while heap.len() > 1 {
             let left = heap.pop().unwrap();
             let right = heap.pop().unwrap();
@@ -76,10 +283,10 @@ impl HuffmanTree {
         HuffmanTree { root, codes }
     }
 
-    fn build_codes(node: &Rc<HuffmanNode>, prefix: Vec<bool>, codes: &mut HashMap<u8, Vec<bool>>) {
+    fn build_codes(node: &Rc<HuffmanNode<S>>, prefix: Vec<bool>, codes: &mut HashMap<S, Vec<bool>>) {
         match &**node {
-            HuffmanNode::Leaf { byte } => {
-                codes.insert(*byte, prefix);
+            HuffmanNode::Leaf { symbol } => {
+                codes.insert(symbol.clone(), prefix);
             }
             HuffmanNode::Internal { left, right } => {
                 let mut left_prefix = prefix.clone();
@@ -93,135 +300,611 @@ impl HuffmanTree {
         }
     }
 
-    pub fn encode(&self, data: &[u8]) -> Vec<bool> {
+    pub fn encode(&self, data: &[S]) -> Vec<bool> {
         let mut encoded = Vec::new();
-        for &byte in data {
-            if let Some(code) = self.codes.get(&byte) {
+        for symbol in data {
+            if let Some(code) = self.codes.get(symbol) {
                 encoded.extend_from_slice(code);
             }
         }
         encoded
     }
 
-    pub fn decode(&self, bits: &[bool]) -> Vec<u8> {
-        let mut result = Vec::new();
+    /// Decodes exactly `count` symbols from `reader`, leaving any trailing
+    /// padding bits for the caller to check with `BitReader::verify_ending`.
+    ///
+    /// There's deliberately no "decode until the reader runs dry" variant:
+    /// without an expected symbol count, decoding can't tell "ran out of
+    /// real data" from "the EOS padding happened to trace a complete path
+    /// to a leaf", which it does whenever the padding is at least as long
+    /// as the tree's all-`1`s code - possible whenever that code is under
+    /// 8 bits, as it is for almost any small-to-medium alphabet. That's not
+    /// just an ambiguous error case, it's silent corruption: a phantom
+    /// extra symbol gets appended to the result. Requiring the caller to
+    /// supply `count` (as [`HuffmanTree::decompress`] does, from the
+    /// header) removes the ambiguity instead of papering over it.
+    pub fn decode_exact(&self, reader: &mut BitReader, count: usize) -> Result<Vec<S>, DecodeError> {
+        let mut result = Vec::with_capacity(count);
         let mut current = &self.root;
 
-        let mut i = 0;
-        loop {
-            let bit = if i < bits.len() { bits[i] } else { false };
-            current = match &**current {
+        while result.len() < count {
+            match &**current {
                 HuffmanNode::Internal { left, right } => {
-                    if !bit {
-                        left
-                    } else {
-                        right
-                    }
+                    let bit = reader.read_bit().ok_or(DecodeError::UnexpectedEof)?;
+                    current = if bit { right } else { left };
                 }
-                HuffmanNode::Leaf { .. } => &self.root,
-            };
-
-            if let HuffmanNode::Leaf { byte } = &**current {
-                result.push(*byte);
-                if i >= bits.len() {
-                    // pad with 0s until we push out a symbol
-                    break;
+                HuffmanNode::Leaf { symbol } => {
+                    result.push(symbol.clone());
+                    current = &self.root;
                 }
-                current = &self.root;
             }
-            i += 1;
         }
 
-        result
+        Ok(result)
+    }
+
+    /// Builds a tree from an explicit, precomputed code table, e.g. a
+    /// fixed table borrowed from another format (HPACK's static Huffman
+    /// codes) rather than one derived from frequencies. Every code is
+    /// inserted bit-by-bit and validated as it goes, so a malformed table
+    /// (duplicate codes, one code that's a prefix of another, or branches
+    /// that are never assigned a leaf) is rejected up front instead of
+    /// producing a tree that silently mis-decodes.
+    pub fn from_codes(codes: impl IntoIterator<Item = (S, Vec<bool>)>) -> Result<Self, HuffmanTreeError> {
+        let mut root: Option<Box<BuildNode<S>>> = None;
+        let mut code_map = HashMap::new();
+
+        for (symbol, bits) in codes {
+            BuildNode::insert_checked(&mut root, &bits, symbol.clone())?;
+            code_map.insert(symbol, bits);
+        }
+
+        let root = root.ok_or(HuffmanTreeError::MissingLeaf)?;
+        root.check_complete()?;
+
+        Ok(HuffmanTree {
+            root: root.into_rc(),
+            codes: code_map,
+        })
     }
 }
 
-fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
-    let mut bits = Vec::new();
-    for &byte in bytes {
-        for i in (0..8).rev() {
-            bits.push((byte >> i) & 1 == 1);
+/// A symbol's code packed into an integer: the low `bits` bits of `value`,
+/// most-significant-bit first. A byte alphabet's codes can be up to 255
+/// bits long in the worst case (a heavily skewed, Fibonacci-shaped
+/// frequency table over the full 256-symbol alphabet), which does not fit
+/// in a `u64` - so this is only ever built for codes that are short enough
+/// to pack; anything longer falls back to the plain `Vec<bool>` path in
+/// [`HuffmanTree::encode_packed`] instead of being represented here.
+#[derive(Debug, Clone, Copy)]
+struct HuffmanValue {
+    value: u64,
+    bits: u32,
+}
+
+impl HuffmanTree<u8> {
+    /// Returns the bit-length of each symbol's code, indexed by byte value,
+    /// with `0` for symbols absent from the tree. Together with
+    /// [`HuffmanTree::from_code_lengths`] this is all that's needed to
+    /// reconstruct an equivalent tree, so a header only has to carry each
+    /// present symbol and its length byte instead of the full code or tree.
+    pub fn code_lengths(&self) -> [u8; 256] {
+        let mut lengths = [0u8; 256];
+        for (&byte, code) in &self.codes {
+            lengths[byte as usize] = code.len() as u8;
+        }
+        lengths
+    }
+
+    /// Rebuilds a tree from code lengths alone by assigning canonical codes:
+    /// symbols ordered by `(length, byte)`, each code one more than the last
+    /// (shifted left when length increases). This is how `decompress`
+    /// reconstructs the codes `compress` used without shipping them.
+    ///
+    /// The running code is tracked as a bit vector rather than a fixed-width
+    /// int, since lengths are a full `u8` and can exceed 64 bits.
+    ///
+    /// `lengths` comes from an attacker-controlled header, so it's validated
+    /// the same way `from_codes` validates an explicit table, rather than
+    /// assumed well-formed.
+    pub fn from_code_lengths(lengths: &[u8; 256]) -> Result<Self, HuffmanTreeError> {
+        let mut symbols: Vec<(u8, u8)> = lengths
+            .iter()
+            .enumerate()
+            .filter(|&(_, &len)| len > 0)
+            .map(|(byte, &len)| (byte as u8, len))
+            .collect();
+        symbols.sort_unstable_by_key(|&(byte, len)| (len, byte));
+
+        // Increments the bit vector as a binary number, MSB first, by
+        // flipping trailing `1`s to `0` and the first `0` it finds to `1`.
+        fn increment(code: &mut [bool]) {
+            for bit in code.iter_mut().rev() {
+                if *bit {
+                    *bit = false;
+                } else {
+                    *bit = true;
+                    return;
+                }
+            }
+        }
+
+        // A single-symbol alphabet is given a length-1 code (see `build`'s
+        // own one-symbol special case) even though that "code" never
+        // actually has to distinguish it from a sibling - there is no
+        // sibling. `check_complete` can't see that distinction: an internal
+        // node with only one child looks identical whether it's this
+        // intentional, harmless case or a genuinely incomplete multi-symbol
+        // table. Skip the check for exactly one symbol and let `into_rc`'s
+        // existing single-child mirroring handle it, the same way `build`
+        // does; for two or more symbols, a one-child internal node can only
+        // mean a real gap in the table.
+        let single_symbol = symbols.len() == 1;
+
+        let mut root: Option<Box<BuildNode<u8>>> = None;
+        let mut codes = HashMap::new();
+        let mut code: Vec<bool> = Vec::new();
+
+        for (byte, len) in symbols {
+            code.resize(len as usize, false);
+            let bits = code.clone();
+            BuildNode::insert_checked(&mut root, &bits, byte)?;
+            codes.insert(byte, bits);
+            increment(&mut code);
+        }
+
+        let root = match root {
+            Some(node) => {
+                if !single_symbol {
+                    node.check_complete()?;
+                }
+                node.into_rc()
+            }
+            None => Rc::new(HuffmanNode::Leaf { symbol: 0 }),
+        };
+
+        Ok(HuffmanTree { root, codes })
+    }
+
+    /// Builds a `[Option<HuffmanValue>; 256]` lookup table from `self.codes`,
+    /// indexed by byte value. [`HuffmanTree::encode_packed`] uses this to
+    /// turn encoding into a table lookup plus a bit-packing loop instead of
+    /// a `HashMap` lookup followed by a `Vec<bool>` walk.
+    ///
+    /// A code longer than 64 bits has no entry here at all - it can't be
+    /// packed into a `HuffmanValue` - so `encode_packed` falls back to
+    /// `self.codes` directly for those symbols.
+    fn packed_codes(&self) -> [Option<HuffmanValue>; 256] {
+        let mut table = [None; 256];
+        for (&byte, code) in &self.codes {
+            if code.len() > 64 {
+                continue;
+            }
+            let value = code.iter().fold(0u64, |acc, &bit| (acc << 1) | bit as u64);
+            table[byte as usize] = Some(HuffmanValue {
+                value,
+                bits: code.len() as u32,
+            });
+        }
+        table
+    }
+
+    /// Table-driven counterpart to `encode`: looks each byte's code up in a
+    /// packed lookup table and shifts it straight into the output via
+    /// `BitWriter::push_value`, never allocating an intermediate `Vec<bool>`.
+    /// Codes over 64 bits (too long to pack into a `HuffmanValue`) fall
+    /// back to pushing the bit vector from `self.codes` directly.
+    pub fn encode_packed(&self, data: &[u8]) -> Vec<u8> {
+        let table = self.packed_codes();
+        let mut writer = BitWriter::new();
+        for &byte in data {
+            match table[byte as usize] {
+                Some(code) => writer.push_value(code.value, code.bits),
+                None => {
+                    if let Some(bits) = self.codes.get(&byte) {
+                        writer.push_bits(bits);
+                    }
+                }
+            }
         }
+        writer.finish()
+    }
+
+    /// Compresses `data` into a self-contained stream: a header of
+    /// canonical code lengths and the original length, followed by the
+    /// packed bitstream. Unlike plain `encode`, the result carries
+    /// everything a matching `decompress` call needs to rebuild the tree
+    /// and recover the exact original bytes.
+    pub fn compress(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        if data.is_empty() {
+            out.extend_from_slice(&0u16.to_be_bytes());
+            out.extend_from_slice(&0u32.to_be_bytes());
+            return out;
+        }
+
+        let mut freqs = HashMap::new();
+        for &byte in data {
+            *freqs.entry(byte).or_insert(0usize) += 1;
+        }
+
+        let lengths = HuffmanTree::build(&freqs).code_lengths();
+        let canonical = HuffmanTree::from_code_lengths(&lengths)
+            .expect("code_lengths() of a tree just built from these lengths is always complete");
+
+        let present = lengths.iter().filter(|&&len| len > 0).count() as u16;
+        out.extend_from_slice(&present.to_be_bytes());
+        for (byte, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                out.push(byte as u8);
+                out.push(len);
+            }
+        }
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&canonical.encode_packed(data));
+
+        out
+    }
+
+    /// Reverses [`HuffmanTree::compress`], rebuilding the tree from the
+    /// header alone.
+    pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut pos = 0;
+        let read_u16 = |pos: &mut usize| -> Result<u16, DecodeError> {
+            let slice = bytes.get(*pos..*pos + 2).ok_or(DecodeError::UnexpectedEof)?;
+            *pos += 2;
+            Ok(u16::from_be_bytes(slice.try_into().unwrap()))
+        };
+        let read_u32 = |pos: &mut usize| -> Result<u32, DecodeError> {
+            let slice = bytes.get(*pos..*pos + 4).ok_or(DecodeError::UnexpectedEof)?;
+            *pos += 4;
+            Ok(u32::from_be_bytes(slice.try_into().unwrap()))
+        };
+
+        let symbol_count = read_u16(&mut pos)?;
+
+        let mut lengths = [0u8; 256];
+        for _ in 0..symbol_count {
+            let byte = *bytes.get(pos).ok_or(DecodeError::UnexpectedEof)?;
+            let len = *bytes.get(pos + 1).ok_or(DecodeError::UnexpectedEof)?;
+            pos += 2;
+            lengths[byte as usize] = len;
+        }
+
+        let original_len = read_u32(&mut pos)? as usize;
+        if original_len == 0 {
+            return Ok(Vec::new());
+        }
+        if symbol_count == 0 {
+            return Err(DecodeError::EmptyCodeTable);
+        }
+
+        let tree = HuffmanTree::from_code_lengths(&lengths)?;
+        let packed = bytes.get(pos..).ok_or(DecodeError::UnexpectedEof)?;
+        let mut reader = BitReader::new(packed);
+        let result = tree.decode_exact(&mut reader, original_len)?;
+        reader.verify_ending(reader.pos)?;
+
+        Ok(result)
     }
-    bits
 }
 
-fn bits_to_bytes(bits: &[bool]) -> Vec<u8> {
-    assert!(bits.len() % 8 == 0, "Number of bits must be divisible by 8");
-    let mut bytes = Vec::new();
+/// Scratch tree used while assigning canonical codes in
+/// [`HuffmanTree::from_code_lengths`]: unlike `HuffmanNode`, its internal
+/// nodes grow one child at a time as codes are inserted.
+enum BuildNode<S> {
+    Leaf(S),
+    Internal(Option<Box<BuildNode<S>>>, Option<Box<BuildNode<S>>>),
+}
+
+impl<S> BuildNode<S> {
+    /// Validates that `code` neither collides with nor overlaps an
+    /// existing one as it's inserted, instead of silently letting the
+    /// shorter code win. Both `from_codes` and `from_code_lengths` build
+    /// their scratch tree this way, since both take code assignments
+    /// (explicit or canonical) that could, in principle, be malformed.
+    fn insert_checked(
+        root: &mut Option<Box<BuildNode<S>>>,
+        code: &[bool],
+        symbol: S,
+    ) -> Result<(), HuffmanTreeError> {
+        match root {
+            None => {
+                if code.is_empty() {
+                    *root = Some(Box::new(BuildNode::Leaf(symbol)));
+                } else {
+                    let mut internal = Box::new(BuildNode::Internal(None, None));
+                    if let BuildNode::Internal(left, right) = internal.as_mut() {
+                        let child = if code[0] { right } else { left };
+                        BuildNode::insert_checked(child, &code[1..], symbol)?;
+                    }
+                    *root = Some(internal);
+                }
+                Ok(())
+            }
+            Some(node) => match node.as_mut() {
+                BuildNode::Leaf(_) => {
+                    if code.is_empty() {
+                        Err(HuffmanTreeError::DuplicateLeaf)
+                    } else {
+                        // A shorter code already claimed this node as a leaf,
+                        // but this code wants to continue past it.
+                        Err(HuffmanTreeError::OrphanedLeaf)
+                    }
+                }
+                BuildNode::Internal(left, right) => {
+                    if code.is_empty() {
+                        // This code wants to end here, but a longer code
+                        // already continues past this point.
+                        Err(HuffmanTreeError::OrphanedLeaf)
+                    } else {
+                        let child = if code[0] { right } else { left };
+                        BuildNode::insert_checked(child, &code[1..], symbol)
+                    }
+                }
+            },
+        }
+    }
 
-    for chunk in bits.chunks(8) {
-        let mut byte = 0u8;
-        for (i, &bit) in chunk.iter().enumerate() {
-            if bit {
-                byte |= 1 << (7 - i);
+    /// Confirms every internal node reached while inserting codes ended up
+    /// with both a `0` and a `1` child, i.e. the code table is complete.
+    fn check_complete(&self) -> Result<(), HuffmanTreeError> {
+        match self {
+            BuildNode::Leaf(_) => Ok(()),
+            BuildNode::Internal(Some(left), Some(right)) => {
+                left.check_complete()?;
+                right.check_complete()
             }
+            BuildNode::Internal(_, _) => Err(HuffmanTreeError::MissingLeaf),
         }
-        bytes.push(byte);
     }
 
-    bytes
+    /// Converts into the immutable `Rc` tree used for encoding/decoding. An
+    /// internal node missing a child (possible only for a degenerate,
+    /// single-symbol alphabet) gets a mirrored dummy sibling, the same way
+    /// [`HuffmanTree::build`] handles that case.
+    fn into_rc(self) -> Rc<HuffmanNode<S>> {
+        match self {
+            BuildNode::Leaf(symbol) => Rc::new(HuffmanNode::Leaf { symbol }),
+            BuildNode::Internal(left, right) => match (left, right) {
+                (Some(left), Some(right)) => Rc::new(HuffmanNode::Internal {
+                    left: left.into_rc(),
+                    right: right.into_rc(),
+                }),
+                (Some(only), None) | (None, Some(only)) => {
+                    let only = only.into_rc();
+                    Rc::new(HuffmanNode::Internal {
+                        left: Rc::clone(&only),
+                        right: only,
+                    })
+                }
+                (None, None) => unreachable!("internal node must gain at least one child"),
+            },
+        }
+    }
 }
 
 fn main() {
-    let freqs = HashMap::from([
-        (b'e', 1270),
-        (b't', 910),
-        (b'a', 820),
-        (b'o', 750),
-        (b'i', 700),
-        (b'n', 670),
-        (b's', 630),
-        (b'h', 610),
-        (b'r', 600),
-        (b'd', 430),
-        (b'l', 400),
-        (b'c', 280),
-        (b'u', 280),
-        (b'm', 240),
-        (b'w', 240),
-        (b'f', 220),
-        (b'g', 200),
-        (b'y', 200),
-        (b'p', 190),
-        (b'b', 150),
-        (b'v', 98),
-        (b'k', 77),
-        (b'j', 15),
-        (b'x', 15),
-        (b'q', 9),
-        (b'z', 7),
-    ]);
-    let tree = HuffmanTree::build(&freqs);
-
     let key = Aes256Gcm::generate_key(&mut OsRng);
     let cipher = Aes256Gcm::new(&key);
     let nonce = Nonce::from(rand::random::<[u8; 12]>());
 
     let plaintext = b"hello world";
+
+    let compressed = HuffmanTree::compress(plaintext);
+    println!("Compressed: {} bytes from {} bytes", compressed.len(), plaintext.len());
+
     let ciphertext = cipher
-        .encrypt(&nonce, plaintext.as_ref())
+        .encrypt(&nonce, compressed.as_ref())
         .expect("encryption failed");
 
-    let encrypted_bits = bytes_to_bits(&ciphertext);
+    let decrypted = cipher.decrypt(&nonce, ciphertext.as_ref()).unwrap();
+    assert_eq!(compressed, decrypted);
 
-    let decoded = tree.decode(&encrypted_bits);
+    let decompressed = HuffmanTree::decompress(&decrypted).expect("decompress failed");
+    assert_eq!(plaintext.as_slice(), decompressed.as_slice());
 
     println!("Plaintext: {:?}", plaintext);
-    println!("Ciphertext (bytes): {:?}", ciphertext);
-    println!("Ciphertext (bits): {:?}", encrypted_bits);
-    println!("Huffman-decoded: {:?}", std::str::from_utf8(&decoded));
+    println!("Decompressed: {:?}", std::str::from_utf8(&decompressed));
+
+    // Building from an explicit code table (e.g. one borrowed from another
+    // format) instead of frequencies is a separate path from `compress`'s
+    // frequency-driven `build`; demonstrate it here so it isn't only ever
+    // exercised by tests.
+    let fixed_codes = vec![(b'a', vec![false]), (b'b', vec![true, false]), (b'c', vec![true, true])];
+    let fixed_tree = HuffmanTree::from_codes(fixed_codes).expect("valid code table");
+    println!("Encoded \"abc\" with a fixed code table: {:?}", fixed_tree.encode(b"abc"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_bytes(data: &[u8]) {
+        let compressed = HuffmanTree::compress(data);
+        let decompressed = HuffmanTree::decompress(&compressed).expect("decompress failed");
+        assert_eq!(decompressed.as_slice(), data);
+    }
+
+    #[test]
+    fn round_trips_empty_input() {
+        round_trip_bytes(b"");
+    }
+
+    #[test]
+    fn round_trips_single_byte() {
+        round_trip_bytes(b"x");
+    }
+
+    #[test]
+    fn round_trips_non_byte_aligned_bitstreams() {
+        // Regression: decoding without an expected symbol count used to be
+        // able to fabricate a trailing symbol out of this string's EOS
+        // padding; `compress`/`decompress` route through `decode_exact`
+        // instead, which doesn't have that ambiguity.
+        round_trip_bytes(b"abcaabab");
+        round_trip_bytes(b"hello world");
+        round_trip_bytes(b"aaaaaaaaaaaaaaaaaaab");
+    }
+
+    #[test]
+    fn from_code_lengths_handles_codes_past_32_bits() {
+        // A Fibonacci-shaped length distribution over 34 symbols forces a
+        // max code length of 33 bits, more than a `u32` canonical-code
+        // accumulator could hold; `from_code_lengths` tracks the running
+        // code as a bit vector instead.
+        let mut lengths = [0u8; 256];
+        for (byte, len) in lengths.iter_mut().take(32).enumerate() {
+            *len = (byte + 1) as u8;
+        }
+        lengths[32] = 33;
+        lengths[33] = 33;
+
+        let tree = HuffmanTree::from_code_lengths(&lengths).expect("valid code table");
+        assert_eq!(tree.code_lengths(), lengths);
+
+        let packed = tree.encode_packed(&[33u8]);
+        let mut reader = BitReader::new(&packed);
+        let decoded = tree.decode_exact(&mut reader, 1).expect("decode failed");
+        assert_eq!(decoded, vec![33u8]);
+    }
+
+    #[test]
+    fn encode_packed_falls_back_for_codes_over_64_bits() {
+        // Lengths 1..=64 exhaust all but 2^-64 of the Kraft budget; splitting
+        // the remainder across two symbols of length 65 keeps the table
+        // complete while forcing a code past the 64-bit `HuffmanValue` limit.
+        let mut lengths = [0u8; 256];
+        for (byte, len) in lengths.iter_mut().take(64).enumerate() {
+            *len = (byte + 1) as u8;
+        }
+        lengths[64] = 65;
+        lengths[65] = 65;
+
+        let tree = HuffmanTree::from_code_lengths(&lengths).expect("valid code table");
+        assert_eq!(tree.code_lengths(), lengths);
+
+        let packed = tree.encode_packed(&[65u8]);
+        let mut reader = BitReader::new(&packed);
+        let decoded = tree.decode_exact(&mut reader, 1).expect("decode failed");
+        assert_eq!(decoded, vec![65u8]);
+    }
+
+    #[test]
+    fn from_code_lengths_rejects_an_oversubscribed_table() {
+        // Three symbols all claiming length 1 leaves no room: only two
+        // 1-bit codes exist, so canonical assignment wraps the third back
+        // onto one already taken instead of ever reaching a valid table.
+        let mut lengths = [0u8; 256];
+        lengths[b'a' as usize] = 1;
+        lengths[b'b' as usize] = 1;
+        lengths[b'c' as usize] = 1;
+
+        assert_eq!(HuffmanTree::from_code_lengths(&lengths).unwrap_err(), HuffmanTreeError::DuplicateLeaf);
+    }
+
+    #[test]
+    fn decompress_rejects_a_stream_with_an_oversubscribed_header() {
+        // Same malformed length table as above, hand-assembled into a
+        // header the way `compress` would, followed by arbitrary bitstream
+        // bytes. `decompress` must reject this rather than silently
+        // decoding garbage.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&3u16.to_be_bytes());
+        bytes.push(b'a');
+        bytes.push(1);
+        bytes.push(b'b');
+        bytes.push(1);
+        bytes.push(b'c');
+        bytes.push(1);
+        bytes.extend_from_slice(&3u32.to_be_bytes());
+        bytes.push(0xFF);
+
+        assert_eq!(
+            HuffmanTree::decompress(&bytes).unwrap_err(),
+            DecodeError::MalformedCodeTable(HuffmanTreeError::DuplicateLeaf)
+        );
+    }
+
+    #[test]
+    fn decompress_rejects_a_nonzero_length_claim_with_an_empty_code_table() {
+        // symbol_count=0, original_len=5, no packed bytes at all: nothing
+        // here could have produced 5 decoded bytes. Must be rejected rather
+        // than falling back to `from_code_lengths`'s empty-table leaf and
+        // fabricating five zero bytes.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0u16.to_be_bytes());
+        bytes.extend_from_slice(&5u32.to_be_bytes());
+
+        assert_eq!(HuffmanTree::decompress(&bytes).unwrap_err(), DecodeError::EmptyCodeTable);
+    }
+
+    #[test]
+    fn from_codes_accepts_a_complete_prefix_code() {
+        let codes = vec![(b'a', vec![false]), (b'b', vec![true, false]), (b'c', vec![true, true])];
+        let tree = HuffmanTree::from_codes(codes).expect("valid code table");
+
+        let bits = tree.encode(b"abc");
+        let mut writer = BitWriter::new();
+        writer.push_bits(&bits);
+        let packed = writer.finish();
+
+        let mut reader = BitReader::new(&packed);
+        let decoded = tree.decode_exact(&mut reader, 3).expect("decode failed");
+        assert_eq!(decoded.as_slice(), b"abc");
+    }
 
-    let mut bits = tree.encode(&decoded);
-    // remove padding bits
-    bits.truncate(bits.len() / 8 * 8);
+    #[test]
+    fn from_codes_rejects_duplicate_codes() {
+        let codes = vec![(b'a', vec![false]), (b'b', vec![false])];
+        assert_eq!(HuffmanTree::from_codes(codes).unwrap_err(), HuffmanTreeError::DuplicateLeaf);
+    }
+
+    #[test]
+    fn from_codes_rejects_a_code_that_is_a_prefix_of_another() {
+        let codes = vec![(b'a', vec![false]), (b'b', vec![false, true])];
+        assert_eq!(HuffmanTree::from_codes(codes).unwrap_err(), HuffmanTreeError::OrphanedLeaf);
+    }
 
-    let ciphertext2 = bits_to_bytes(&bits);
-    assert_eq!(ciphertext, ciphertext);
+    #[test]
+    fn from_codes_rejects_an_incomplete_code_table() {
+        let codes = vec![(b'a', vec![false])]; // no code ever claims the `1` branch
+        assert_eq!(HuffmanTree::from_codes(codes).unwrap_err(), HuffmanTreeError::MissingLeaf);
+    }
 
-    let plaintext2 = cipher.decrypt(&nonce, ciphertext2.as_ref()).unwrap();
+    #[test]
+    fn encode_packed_matches_bit_based_encode() {
+        let mut freqs = HashMap::new();
+        for &byte in b"mississippi" {
+            *freqs.entry(byte).or_insert(0usize) += 1;
+        }
+        let tree = HuffmanTree::build(&freqs);
 
-    assert_eq!(plaintext.as_slice(), &plaintext2);
+        let bits = tree.encode(b"mississippi");
+        let mut writer = BitWriter::new();
+        writer.push_bits(&bits);
+        let expected = writer.finish();
 
-    println!("Plaintext2: {:?}", std::str::from_utf8(&plaintext2));
+        assert_eq!(tree.encode_packed(b"mississippi"), expected);
+    }
+
+    #[test]
+    fn supports_generic_symbol_alphabets() {
+        let data: Vec<char> = "mississippi".chars().collect();
+        let mut freqs = HashMap::new();
+        for &ch in &data {
+            *freqs.entry(ch).or_insert(0usize) += 1;
+        }
+        let tree = HuffmanTree::build(&freqs);
+
+        let bits = tree.encode(&data);
+        let mut writer = BitWriter::new();
+        writer.push_bits(&bits);
+        let packed = writer.finish();
+
+        let mut reader = BitReader::new(&packed);
+        let decoded = tree.decode_exact(&mut reader, data.len()).expect("decode failed");
+        assert_eq!(decoded, data);
+    }
 }